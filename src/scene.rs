@@ -1,14 +1,24 @@
 
+use std::cell::RefCell;
 use std::collections::HashMap;
+use std::io::{ File, Reader };
+use std::ops::{ Deref, DerefMut };
+use std::path::Path;
 use uuid::Uuid;
 
 use graphics::*;
+use graphics::math::{ identity, multiply, invert, transform_pos };
 
 use event::GenericEvent;
 use ai_behavior::{
     Behavior,
     State,
     Running,
+    Sequence,
+    Wait,
+    WaitForever,
+    While,
+    Action,
 };
 
 use sprite::Sprite;
@@ -18,12 +28,389 @@ use animation::{
     AnimationState,
 };
 
+/// The kind of lifecycle transition an `AnimationEvent` reports
+#[derive(Clone, PartialEq, Eq, Show)]
+pub enum AnimationEventKind {
+    /// The behavior ran to completion (or failed) and was dropped from
+    /// `running`, or a `PlaybackMode::Once` sprite-sheet clip reached its
+    /// last frame and was paused there (the clip stays registered)
+    Completed,
+    /// A sprite-sheet clip reached the end of its sheet and started over
+    /// (`PlaybackMode::Loop`) or reversed direction (`PlaybackMode::PingPong`).
+    ///
+    /// Not raised for a `Behavior<Animation>` tree's own loops (e.g. a
+    /// `SequenceSet` block with `loop`, which compiles to
+    /// `While(WaitForever, Sequence(..))`): `ai_behavior::State::event` only
+    /// reports the whole tree's status, not a per-iteration boundary inside
+    /// it, and a `While` gated on `WaitForever` never leaves `Running` to
+    /// report through in the first place. Detecting that would need a hook
+    /// into `ai_behavior` itself, which this crate doesn't provide.
+    Looped,
+    /// The behavior was paused, either directly or through `toggle`
+    Paused,
+    /// The behavior was resumed, either directly or through `toggle`
+    Resumed,
+    /// The behavior was stopped explicitly via `stop` or `stop_all`
+    Stopped,
+}
+
+/// Reports an animation-lifecycle transition for a sprite's behavior.
+///
+/// Events are queued while `Scene::event` mutates `running` and drained
+/// once every sprite has been updated, then passed to the callbacks
+/// registered with `on_animation_event` and returned from `event`. A
+/// callback only receives the event, not the `Scene`; to chain animations
+/// (e.g. walk -> idle), start them from the events `event` returns.
+#[derive(Clone, Show)]
+pub struct AnimationEvent {
+    /// The sprite the animation is running on
+    pub sprite_id: Uuid,
+    /// The behavior that transitioned
+    pub behavior: Behavior<Animation>,
+    /// What kind of transition occurred
+    pub kind: AnimationEventKind,
+}
+
+/// A per-channel offset from a sprite's starting transform, sampled by a
+/// single behavior during phase one of a `Scene::event` update. Several of
+/// these (one per behavior running on a sprite) are blended together in
+/// phase two instead of each behavior writing through to the `Sprite`.
+#[derive(Clone, Show)]
+pub struct TransformDelta {
+    /// Horizontal offset from the starting position
+    pub dx: f64,
+    /// Vertical offset from the starting position
+    pub dy: f64,
+    /// Rotation offset from the starting rotation, in degrees
+    pub d_rotation: f64,
+    /// Scale offset from the starting scale
+    pub d_scale: f64,
+    /// Alpha offset from the starting alpha
+    pub d_alpha: f64,
+}
+
+impl TransformDelta {
+    /// A delta that leaves the sprite's transform unchanged
+    pub fn zero() -> TransformDelta {
+        TransformDelta { dx: 0.0, dy: 0.0, d_rotation: 0.0, d_scale: 0.0, d_alpha: 0.0 }
+    }
+}
+
+/// Combine the weighted per-behavior samples of a single sprite into one
+/// `TransformDelta`, normalizing the weights if they sum to more than `1.0`.
+/// Doesn't depend on `Scene<I>`, so it's a free function rather than a
+/// method taking an unused `I: ImageSize` parameter.
+fn combine(samples: &Vec<(TransformDelta, f64)>) -> TransformDelta {
+    let total_weight = samples.iter().fold(0.0, |acc, &(_, w)| acc + w);
+    let scale = if total_weight > 1.0 { 1.0 / total_weight } else { 1.0 };
+
+    let mut combined = TransformDelta::zero();
+    let mut reference: Option<f64> = None;
+    for &(ref delta, weight) in samples.iter() {
+        let w = weight * scale;
+        combined.dx += delta.dx * w;
+        combined.dy += delta.dy * w;
+        // shortest-arc: fold the first rotation delta into (-180, 180], and
+        // every later one into the half-turn either side of that first one,
+        // so deltas on opposite sides of the wrap blend through 180
+        let around = reference.unwrap_or(0.0);
+        let mut d_rotation = (delta.d_rotation - around) % 360.0;
+        if d_rotation > 180.0 { d_rotation -= 360.0; }
+        if d_rotation <= -180.0 { d_rotation += 360.0; }
+        d_rotation += around;
+        if reference.is_none() { reference = Some(d_rotation); }
+        combined.d_rotation += d_rotation * w;
+        combined.d_scale += delta.d_scale * w;
+        combined.d_alpha += delta.d_alpha * w;
+    }
+    combined
+}
+
+/// A texture-atlas sheet: an ordered list of sub-rectangles of one image,
+/// cycled through at `fps` frames per second by a `Scene`'s sprite-sheet
+/// clips.
+#[derive(Clone)]
+pub struct SpriteSheet {
+    /// The sub-rectangle of the owning sprite's image shown at each frame
+    pub frames: Vec<SourceRectangle>,
+    /// Playback rate, in frames per second
+    pub fps: f64,
+}
+
+impl SpriteSheet {
+    /// Create a sprite sheet from an ordered list of frame rectangles
+    pub fn new(frames: Vec<SourceRectangle>, fps: f64) -> SpriteSheet {
+        SpriteSheet { frames: frames, fps: fps }
+    }
+}
+
+/// How a sprite-sheet clip behaves once it reaches the last frame
+#[derive(Clone, PartialEq, Eq, Show)]
+pub enum PlaybackMode {
+    /// Jump back to the first frame and keep going
+    Loop,
+    /// Reverse direction at each end, like a bouncing ball
+    PingPong,
+    /// Hold on the last frame and pause
+    Once,
+}
+
+/// Identifies a `SpriteSheet` that hasn't been materialized yet. Opaque to
+/// `Scene`; only the loader closure passed to `set_sheet_loader` knows how
+/// to turn one into real frame data.
+#[derive(Clone)]
+pub struct SheetDescriptor {
+    /// Loader-defined key, e.g. an atlas name or file path
+    pub key: String,
+}
+
+impl SheetDescriptor {
+    /// Create a descriptor from a loader-defined key
+    pub fn new(key: String) -> SheetDescriptor {
+        SheetDescriptor { key: key }
+    }
+}
+
+/// A sprite sheet's frame data, materialized on first use rather than at
+/// `play_sprite_sheet_lazy` time.
+enum SheetSource {
+    Unloaded(SheetDescriptor),
+    Loaded(SpriteSheet),
+}
+
+/// One sprite-sheet animation in progress, driving a single sprite's
+/// `src_rect` by advancing `current_frame` at `sheet.fps`. Identified the
+/// same way as any other running animation: by the owning sprite's `Uuid`
+/// plus the `Behavior<Animation>` the caller registered it under, so
+/// `pause`/`resume`/`toggle`/`stop`/`stop_all` reach sprite-sheet clips
+/// exactly as they reach ordinary behaviors.
+struct SheetClip {
+    sheet: SheetSource,
+    mode: PlaybackMode,
+    current_frame: uint,
+    direction: int,
+    elapsed: f64,
+    paused: bool,
+}
+
+impl SheetClip {
+    /// The loaded sheet data, if `load_if_needed` has already resolved it
+    fn loaded(&self) -> Option<&SpriteSheet> {
+        match self.sheet {
+            SheetSource::Loaded(ref sheet) => Some(sheet),
+            SheetSource::Unloaded(_) => None,
+        }
+    }
+
+    fn last_frame(&self) -> uint {
+        match self.loaded() {
+            Some(sheet) if sheet.frames.len() > 0 => sheet.frames.len() - 1,
+            _ => 0,
+        }
+    }
+
+    /// Advance by exactly one frame according to the playback mode, reporting
+    /// a lifecycle transition when the clip wraps (`Looped`) or, for `Once`,
+    /// holds on its last frame for the first time (`Completed`).
+    fn step(&mut self) -> Option<AnimationEventKind> {
+        let last = self.last_frame();
+        // a 1-frame (or not-yet-loaded) sheet has nowhere to wrap to, so
+        // `current_frame >= last` is trivially true every tick; only a
+        // sheet with more than one frame can meaningfully "loop"
+        let multi_frame = match self.loaded() {
+            Some(sheet) => sheet.frames.len() > 1,
+            None => false,
+        };
+        match self.mode {
+            PlaybackMode::Loop => {
+                if self.current_frame >= last {
+                    self.current_frame = 0;
+                    if multi_frame { Some(AnimationEventKind::Looped) } else { None }
+                } else {
+                    self.current_frame += 1;
+                    None
+                }
+            },
+            PlaybackMode::Once => {
+                if self.current_frame < last {
+                    self.current_frame += 1;
+                    None
+                } else if !self.paused {
+                    self.paused = true;
+                    Some(AnimationEventKind::Completed)
+                } else {
+                    None
+                }
+            },
+            PlaybackMode::PingPong => {
+                let next = self.current_frame as int + self.direction;
+                if next > last as int {
+                    self.direction = -1;
+                    self.current_frame = if last >= 1 { last - 1 } else { 0 };
+                    if multi_frame { Some(AnimationEventKind::Looped) } else { None }
+                } else if next < 0 {
+                    self.direction = 1;
+                    self.current_frame = if last >= 1 { 1 } else { 0 };
+                    if multi_frame { Some(AnimationEventKind::Looped) } else { None }
+                } else {
+                    self.current_frame = next as uint;
+                    None
+                }
+            },
+        }
+    }
+}
+
+/// Why loading or looking up a `SequenceSet` entry failed
+#[derive(Show)]
+pub enum SeqError {
+    /// `run_named` was asked for a sequence that isn't in the set
+    SequenceNotFound(String),
+    /// The sequence file couldn't be read
+    Io(String),
+    /// The sequence file was read but didn't parse
+    Parse(String),
+}
+
+/// A named set of animation sequences loaded from a declarative data file,
+/// so designers can author `Sequence`/`Wait`/`While`/`Action` behavior trees
+/// without recompiling. Each line of the file is one of:
+///
+/// - a sequence name on its own, starting a new block (blocks are
+///   separated by a blank line)
+/// - `loop`, only as the line right after the name, wrapping the rest of
+///   the block in a `While`
+/// - `wait <duration>`
+/// - `action <name> <param> <param> ...`, where `<name>` and the params are
+///   handed to the `build_action` closure passed to `SequenceSet::load` to
+///   build the concrete `Animation` leaf (the sequence file doesn't know
+///   what animations the game defines, only how to sequence them)
+pub struct SequenceSet {
+    sequences: HashMap<String, Behavior<Animation>>,
+}
+
+impl SequenceSet {
+    /// Load a `SequenceSet` from a declarative sequence file at `path`,
+    /// using `build_action` to turn each `action <name> <params>` line into
+    /// a concrete `Animation` leaf.
+    pub fn load<F>(path: &Path, build_action: F) -> Result<SequenceSet, SeqError>
+        where F: Fn(&str, &[f64]) -> Animation
+    {
+        let mut file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) => return Err(SeqError::Io(e.to_string())),
+        };
+        let text = match file.read_to_string() {
+            Ok(text) => text,
+            Err(e) => return Err(SeqError::Io(e.to_string())),
+        };
+        SequenceSet::parse(text.as_slice(), build_action)
+    }
+
+    fn parse<F>(text: &str, build_action: F) -> Result<SequenceSet, SeqError>
+        where F: Fn(&str, &[f64]) -> Animation
+    {
+        let mut sequences = HashMap::new();
+
+        for block in text.split_str("\n\n") {
+            let lines: Vec<&str> = block.lines()
+                .map(|line| line.trim())
+                .filter(|line| line.len() > 0 && !line.starts_with("#"))
+                .collect();
+            if lines.len() == 0 {
+                continue;
+            }
+
+            let name = lines[0].to_string();
+            let mut body = lines.slice_from(1);
+            let looped = body.len() > 0 && body[0] == "loop";
+            if looped {
+                body = body.slice_from(1);
+            }
+
+            let mut steps = Vec::new();
+            for line in body.iter() {
+                let parts: Vec<&str> = line.split(' ').filter(|p| p.len() > 0).collect();
+                if parts.len() == 2 && parts[0] == "wait" {
+                    match parts[1].parse() {
+                        Some(d) => steps.push(Wait(d)),
+                        None => return Err(SeqError::Parse(
+                            format!("`{}`: `wait` needs a numeric duration", name))),
+                    }
+                } else if parts.len() >= 2 && parts[0] == "action" {
+                    let action_name = parts[1];
+                    let mut args = Vec::new();
+                    for p in parts[2..].iter() {
+                        match p.parse() {
+                            Some(v) => args.push(v),
+                            None => return Err(SeqError::Parse(format!(
+                                "`{}`: `action {}` has a non-numeric parameter `{}`",
+                                name, action_name, p))),
+                        }
+                    }
+                    steps.push(Action(build_action(action_name, args.as_slice())));
+                } else {
+                    return Err(SeqError::Parse(
+                        format!("`{}`: unrecognized step `{}`", name, line)));
+                }
+            }
+
+            let behavior = if looped {
+                While(Box::new(WaitForever), Box::new(Sequence(steps)))
+            } else {
+                Sequence(steps)
+            };
+            sequences.insert(name, behavior);
+        }
+
+        Ok(SequenceSet { sequences: sequences })
+    }
+
+    /// Look up a sequence by name
+    pub fn get(&self, name: &str) -> Option<&Behavior<Animation>> {
+        self.sequences.get(name)
+    }
+}
+
 /// A scene is used to manage sprite's life and run animation with sprite
 pub struct Scene<I: ImageSize> {
     children: Vec<Sprite<I>>,
     children_index: HashMap<Uuid, uint>,
     running: HashMap<Uuid,
-        Vec<(Behavior<Animation>, State<Animation, AnimationState>, bool)>>,
+        Vec<(Behavior<Animation>, State<Animation, AnimationState>, bool, f64)>>,
+    events: Vec<AnimationEvent>,
+    callbacks: HashMap<Uuid, Vec<Box<FnMut(&AnimationEvent) + 'static>>>,
+    world_cache: RefCell<HashMap<Uuid, Matrix2d>>,
+    sheets: HashMap<Uuid, Vec<(Behavior<Animation>, SheetClip)>>,
+    sheet_loader: Option<Box<FnMut(&SheetDescriptor) -> SpriteSheet + 'static>>,
+}
+
+/// A handle to a sprite borrowed mutably through `Scene::child_mut`.
+///
+/// Derefs to `&Sprite<I>`/`&mut Sprite<I>` so it is used just like the plain
+/// reference `child_mut` used to return. On drop, it invalidates the scene's
+/// cached world transforms, since the sprite it guarded (and therefore
+/// everything below it) may have just changed shape.
+pub struct SpriteGuard<'a, I: ImageSize + 'a> {
+    sprite: &'a mut Sprite<I>,
+    world_cache: &'a RefCell<HashMap<Uuid, Matrix2d>>,
+}
+
+impl<'a, I: ImageSize> Deref for SpriteGuard<'a, I> {
+    type Target = Sprite<I>;
+    fn deref(&self) -> &Sprite<I> { self.sprite }
+}
+
+impl<'a, I: ImageSize> DerefMut for SpriteGuard<'a, I> {
+    fn deref_mut(&mut self) -> &mut Sprite<I> { self.sprite }
+}
+
+impl<'a, I: ImageSize> Drop for SpriteGuard<'a, I> {
+    fn drop(&mut self) {
+        // conservative: a mutation anywhere in the tree can change any
+        // descendant's combined matrix, so the whole cache is invalidated
+        self.world_cache.borrow_mut().clear();
+    }
 }
 
 impl<I: ImageSize> Scene<I> {
@@ -33,61 +420,449 @@ impl<I: ImageSize> Scene<I> {
             children: Vec::new(),
             children_index: HashMap::new(),
             running: HashMap::new(),
+            events: Vec::new(),
+            callbacks: HashMap::new(),
+            world_cache: RefCell::new(HashMap::new()),
+            sheets: HashMap::new(),
+            sheet_loader: None,
+        }
+    }
+
+    /// Register the closure used to materialize `SheetDescriptor`s the
+    /// first time a lazily-started sprite-sheet clip is actually needed.
+    pub fn set_sheet_loader(&mut self, loader: Box<FnMut(&SheetDescriptor) -> SpriteSheet + 'static>) {
+        self.sheet_loader = Some(loader);
+    }
+
+    /// Start a frame-indexed sprite-sheet clip on a sprite, identified the
+    /// same way `run` identifies a behavior: by `sprite_id` plus `animation`.
+    /// Control it with `goto_frame`/`next_frame`/`prev_frame`/
+    /// `stop_sprite_sheet`, or with `pause`/`resume`/`toggle`/`stop` the same
+    /// as any other registered animation.
+    pub fn play_sprite_sheet(&mut self, sprite_id: Uuid, animation: &Behavior<Animation>,
+                              sheet: SpriteSheet, mode: PlaybackMode) {
+        self.start_sprite_sheet(sprite_id, animation, SheetSource::Loaded(sheet), mode)
+    }
+
+    /// Start a sprite-sheet clip whose frame data is not loaded until the
+    /// clip is first reached by `event` (or queried via `frame_rect`),
+    /// rather than when this is called. Keeps construction cheap for scenes
+    /// where most registered clips are paused or never become visible.
+    pub fn play_sprite_sheet_lazy(&mut self, sprite_id: Uuid, animation: &Behavior<Animation>,
+                                   descriptor: SheetDescriptor, mode: PlaybackMode) {
+        self.start_sprite_sheet(sprite_id, animation, SheetSource::Unloaded(descriptor), mode)
+    }
+
+    fn start_sprite_sheet(&mut self, sprite_id: Uuid, animation: &Behavior<Animation>,
+                           sheet: SheetSource, mode: PlaybackMode) {
+        use std::collections::hash_map::Entry::{ Vacant, Occupied };
+        let clips = match self.sheets.entry(sprite_id) {
+            Vacant(entry) => entry.set(Vec::new()),
+            Occupied(entry) => entry.into_mut(),
+        };
+        clips.push((animation.clone(), SheetClip {
+            sheet: sheet,
+            mode: mode,
+            current_frame: 0,
+            direction: 1,
+            elapsed: 0.0,
+            paused: false,
+        }));
+    }
+
+    fn find_sheet(&self, sprite_id: Uuid, animation: &Behavior<Animation>) -> Option<uint> {
+        match self.sheets.get(&sprite_id) {
+            Some(clips) => {
+                for i in range(0, clips.len()) {
+                    let (ref b, _) = clips[i];
+                    if b == animation {
+                        return Some(i);
+                    }
+                }
+                None
+            },
+            None => None,
+        }
+    }
+
+    /// Resolve a clip's `SheetSource::Unloaded` descriptor into real frame
+    /// data via the registered loader, if it hasn't been resolved already.
+    /// A no-op if the clip is already loaded or no loader is registered.
+    fn load_if_needed(clip: &mut SheetClip,
+                       loader: &mut Option<Box<FnMut(&SheetDescriptor) -> SpriteSheet + 'static>>) {
+        let resolved = match clip.sheet {
+            SheetSource::Unloaded(ref descriptor) => {
+                match *loader {
+                    Some(ref mut load) => Some((*load)(descriptor)),
+                    None => None,
+                }
+            },
+            SheetSource::Loaded(_) => None,
+        };
+        if let Some(sheet) = resolved {
+            clip.sheet = SheetSource::Loaded(sheet);
+        }
+    }
+
+    /// The `src_rect` of a clip's current frame, resolving its sheet data
+    /// first if it hasn't been loaded yet.
+    pub fn frame_rect(&mut self, sprite_id: Uuid, animation: &Behavior<Animation>) -> Option<SourceRectangle> {
+        let index = match self.find_sheet(sprite_id.clone(), animation) {
+            Some(i) => i,
+            None => return None,
+        };
+        let Scene { ref mut sheets, ref mut sheet_loader, .. } = *self;
+        let clip = &mut sheets.get_mut(&sprite_id).unwrap()[index].1;
+        Scene::<I>::load_if_needed(clip, sheet_loader);
+        match clip.loaded() {
+            Some(sheet) if sheet.frames.len() > 0 => Some(sheet.frames[clip.current_frame].clone()),
+            _ => None,
+        }
+    }
+
+    /// Snap a sprite-sheet clip to an explicit frame, clamped to the sheet's
+    /// length, optionally pausing it so it holds on that frame. Resolves the
+    /// clip's sheet data first if it hasn't been loaded yet, the same as
+    /// `frame_rect`, so a lazy clip's first `goto_frame` doesn't clamp
+    /// against a not-yet-loaded sheet's frame count of zero.
+    pub fn goto_frame(&mut self, sprite_id: Uuid, animation: &Behavior<Animation>, frame: uint, stop: bool) {
+        let index = match self.find_sheet(sprite_id.clone(), animation) {
+            Some(i) => i,
+            None => return,
+        };
+        let Scene { ref mut sheets, ref mut sheet_loader, .. } = *self;
+        let clip = &mut sheets.get_mut(&sprite_id).unwrap()[index].1;
+        Scene::<I>::load_if_needed(clip, sheet_loader);
+        clip.current_frame = ::std::cmp::min(frame, clip.last_frame());
+        clip.elapsed = 0.0;
+        clip.paused = stop;
+    }
+
+    /// Advance a sprite-sheet clip by one frame
+    pub fn next_frame(&mut self, sprite_id: Uuid, animation: &Behavior<Animation>, stop: bool) {
+        let index = match self.find_sheet(sprite_id.clone(), animation) {
+            Some(i) => i,
+            None => return,
+        };
+        let Scene { ref mut sheets, ref mut sheet_loader, .. } = *self;
+        let clip = &mut sheets.get_mut(&sprite_id).unwrap()[index].1;
+        Scene::<I>::load_if_needed(clip, sheet_loader);
+        let frame = if clip.current_frame < clip.last_frame() { clip.current_frame + 1 } else { clip.last_frame() };
+        clip.current_frame = frame;
+        clip.elapsed = 0.0;
+        clip.paused = stop;
+    }
+
+    /// Step a sprite-sheet clip back by one frame
+    pub fn prev_frame(&mut self, sprite_id: Uuid, animation: &Behavior<Animation>, stop: bool) {
+        let index = match self.find_sheet(sprite_id.clone(), animation) {
+            Some(i) => i,
+            None => return,
+        };
+        let Scene { ref mut sheets, ref mut sheet_loader, .. } = *self;
+        let clip = &mut sheets.get_mut(&sprite_id).unwrap()[index].1;
+        Scene::<I>::load_if_needed(clip, sheet_loader);
+        clip.current_frame = if clip.current_frame > 0 { clip.current_frame - 1 } else { 0 };
+        clip.elapsed = 0.0;
+        clip.paused = stop;
+    }
+
+    /// Stop a sprite-sheet clip entirely, the same as `stop` does for an
+    /// ordinary behavior
+    pub fn stop_sprite_sheet(&mut self, sprite_id: Uuid, animation: &Behavior<Animation>) {
+        let index = match self.find_sheet(sprite_id.clone(), animation) {
+            Some(i) => i,
+            None => return,
+        };
+        let (b, _) = self.sheets.get_mut(&sprite_id).unwrap().remove(index);
+        self.events.push(AnimationEvent {
+            sprite_id: sprite_id,
+            behavior: b,
+            kind: AnimationEventKind::Stopped,
+        });
+    }
+
+    /// Register a callback that is invoked for every `AnimationEvent`
+    /// raised for `sprite_id`'s behaviors, in addition to the events
+    /// returned from `event`.
+    pub fn on_animation_event(&mut self, sprite_id: Uuid,
+                               callback: Box<FnMut(&AnimationEvent) + 'static>) {
+        use std::collections::hash_map::Entry::{ Vacant, Occupied };
+        let callbacks = match self.callbacks.entry(sprite_id) {
+            Vacant(entry) => entry.set(Vec::new()),
+            Occupied(entry) => entry.into_mut(),
+        };
+        callbacks.push(callback);
+    }
+
+    /// The `TransformDelta` a sample sprite accumulated relative to the
+    /// un-animated starting sprite, for blending in `combine`. `update`
+    /// still writes straight through to whatever `Sprite` it's given (its
+    /// signature is unchanged from before this series), so phase one gets
+    /// its delta by diffing a scratch clone it wrote through to against
+    /// `base`, rather than by changing what `update` returns.
+    fn delta_from(base: &Sprite<I>, sample: &Sprite<I>) -> TransformDelta {
+        let (bx, by) = base.get_position();
+        let (sx, sy) = sample.get_position();
+        let (b_scale, _) = base.get_scale();
+        let (s_scale, _) = sample.get_scale();
+        TransformDelta {
+            dx: sx - bx,
+            dy: sy - by,
+            d_rotation: sample.get_rotation() - base.get_rotation(),
+            d_scale: s_scale - b_scale,
+            d_alpha: sample.get_alpha() - base.get_alpha(),
         }
     }
 
-    /// Update animation's state
-    pub fn event<E: GenericEvent>(&mut self, e: &E) {
+    /// Update animation's state, returning the lifecycle events
+    /// (completions, loops, pauses, resumes, stops) raised this frame.
+    ///
+    /// Behaviors no longer write straight through to their `Sprite`: each
+    /// non-paused behavior is first sampled against its own scratch clone of
+    /// the sprite's starting transform (phase one), producing a weighted
+    /// `TransformDelta`, and only once every behavior on that sprite has
+    /// been sampled are the deltas blended into the real `Sprite` (phase
+    /// two). This is what lets two behaviors that both touch
+    /// position/rotation/scale blend instead of the last one clobbering the
+    /// others, without requiring any change to `AnimationState::update`
+    /// itself, which still takes `&mut Sprite<I>` and writes through to it.
+    pub fn event<E: GenericEvent>(&mut self, e: &E) -> Vec<AnimationEvent> {
         // regenerate the animations and their states
         let running = self.running.clone();
         self.running.clear();
 
         for (id, animations) in running.into_iter() {
             let mut new_animations = Vec::new();
+            let mut samples = Vec::new();
 
-            for (b, mut a, paused) in animations.into_iter() {
+            // phase one: sample every non-paused behavior against its own
+            // scratch clone of the sprite's starting transform, so a
+            // write-through `update` never touches the real sprite directly
+            let base = self.child(id.clone()).unwrap().clone();
+            for (b, mut a, paused, weight) in animations.into_iter() {
                 if paused {
-                    new_animations.push((b, a, paused));
+                    new_animations.push((b, a, paused, weight));
                     continue;
                 }
 
-                let sprite = self.child_mut(id.clone()).unwrap();
+                let mut sample = base.clone();
                 let (status, _) = a.event(e, |_, dt, animation, s| {
                     let (state, status, remain) = {
                         let start_state;
                         let state = match *s {
-                            None => { start_state = animation.to_state(sprite); &start_state },
+                            None => { start_state = animation.to_state(&mut sample); &start_state },
                             Some(ref state) => state,
                         };
-                        state.update(sprite, dt)
+                        state.update(&mut sample, dt)
                     };
                     *s = state;
                     (status, remain)
                 });
 
+                // contribute this frame's delta whether or not the behavior
+                // is still running, so a completing behavior (e.g. a MoveTo
+                // on its last frame) still reaches its exact target instead
+                // of stopping one frame short
+                samples.push((Scene::<I>::delta_from(&base, &sample), weight));
+
                 match status {
                     // the behavior is still running, add it for next update
                     Running => {
-                        new_animations.push((b, a, paused));
+                        new_animations.push((b, a, paused, weight));
+                    },
+                    _ => {
+                        // the behavior transitioned away from `Running`;
+                        // queue the event rather than firing it here, so
+                        // callbacks only run once every sprite is updated
+                        self.events.push(AnimationEvent {
+                            sprite_id: id.clone(),
+                            behavior: b,
+                            kind: AnimationEventKind::Completed,
+                        });
                     },
-                    _ => {},
                 }
             }
 
+            // phase two: blend every sample into the real sprite at once
+            if samples.len() > 0 {
+                let combined = combine(&samples);
+                let sprite = self.child_mut(id.clone()).unwrap();
+                sprite.set_position(
+                    base.get_position().0 + combined.dx,
+                    base.get_position().1 + combined.dy);
+                sprite.set_rotation(base.get_rotation() + combined.d_rotation);
+                let (sx, sy) = base.get_scale();
+                sprite.set_scale(sx + combined.d_scale, sy + combined.d_scale);
+                sprite.set_alpha(base.get_alpha() + combined.d_alpha);
+            }
+
             if new_animations.len() > 0 {
                 self.running.insert(id, new_animations);
             }
         }
+
+        self.advance_sprite_sheets(e);
+
+        // every behavior and sprite-sheet clip has now been updated, so
+        // drain the queue and fan events out to per-sprite callbacks
+        // before returning them to the caller
+        let events = ::std::mem::replace(&mut self.events, Vec::new());
+        for event in events.iter() {
+            if let Some(callbacks) = self.callbacks.get_mut(&event.sprite_id) {
+                for callback in callbacks.iter_mut() {
+                    (*callback)(event);
+                }
+            }
+        }
+        events
+    }
+
+    /// Accumulate `dt` into every running sprite-sheet clip, stepping its
+    /// frame at `sheet.fps`, queuing a lifecycle event for any clip that
+    /// loops or (for `PlaybackMode::Once`) completes, and pushing the
+    /// resulting `src_rect` onto each clip's owning sprite.
+    fn advance_sprite_sheets<E: GenericEvent>(&mut self, e: &E) {
+        let mut dt = 0.0f64;
+        e.update(|args| dt = args.dt);
+        if dt == 0.0 {
+            return;
+        }
+
+        {
+            let Scene { ref mut sheets, ref mut sheet_loader, ref mut events, .. } = *self;
+            for (sprite_id, clips) in sheets.iter_mut() {
+                for entry in clips.iter_mut() {
+                    let (ref animation, ref mut clip) = *entry;
+                    if clip.paused {
+                        continue;
+                    }
+                    // first non-paused touch of this clip resolves its sheet data
+                    Scene::<I>::load_if_needed(clip, sheet_loader);
+
+                    let (fps, len) = match clip.loaded() {
+                        Some(sheet) => (sheet.fps, sheet.frames.len()),
+                        None => continue,
+                    };
+                    if len == 0 {
+                        continue;
+                    }
+
+                    clip.elapsed += dt;
+                    let frame_time = 1.0 / fps;
+                    while clip.elapsed >= frame_time && !clip.paused {
+                        clip.elapsed -= frame_time;
+                        if let Some(kind) = clip.step() {
+                            events.push(AnimationEvent {
+                                sprite_id: sprite_id.clone(),
+                                behavior: animation.clone(),
+                                kind: kind,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        let frames: Vec<(Uuid, SourceRectangle)> = self.sheets.iter()
+            .flat_map(|(sprite_id, clips)| {
+                let sprite_id = sprite_id.clone();
+                clips.iter().filter_map(move |entry| match entry.1.loaded() {
+                    Some(sheet) if sheet.frames.len() > 0 =>
+                        Some((sprite_id.clone(), sheet.frames[entry.1.current_frame].clone())),
+                    _ => None,
+                })
+            })
+            .collect();
+        for (sprite_id, rect) in frames.into_iter() {
+            if let Some(mut sprite) = self.child_mut(sprite_id) {
+                sprite.set_src_rect(rect);
+            }
+        }
     }
 
     /// Render this scene
+    ///
+    /// Also warms the per-node world-transform cache for every sprite in
+    /// the tree, so a `world_transform`/`point_to_local` query issued right
+    /// after a draw (e.g. to hit-test the frame just rendered) is O(1).
     pub fn draw<B: BackEnd<I>>(&self, c: &Context, b: &mut B) {
+        let top_level: Vec<Uuid> = self.children.iter().map(|child| child.id()).collect();
+        for id in top_level.into_iter() {
+            self.cache_world_transforms(id);
+        }
+
         for child in self.children.iter() {
             child.draw(c, b);
         }
     }
 
-    /// Register animation with sprite
+    /// Recursively populate `world_cache` for `id` and everything below it
+    fn cache_world_transforms(&self, id: Uuid) {
+        self.world_transform(id.clone());
+        let descendants = match self.child(id) {
+            Some(sprite) => sprite.children().iter().map(|c| c.id()).collect(),
+            None => Vec::new(),
+        };
+        for descendant in descendants.into_iter() {
+            self.cache_world_transforms(descendant);
+        }
+    }
+
+    /// The local transform (position, rotation, scale composed in that
+    /// order) of a single sprite, independent of its ancestors
+    fn local_transform(sprite: &Sprite<I>) -> Matrix2d {
+        let (x, y) = sprite.get_position();
+        let rotation = sprite.get_rotation();
+        let (sx, sy) = sprite.get_scale();
+        Context::new().trans(x, y).rot_deg(rotation).scale(sx, sy).transform
+    }
+
+    /// Build the chain of local transforms from the root down to `id`,
+    /// depth-first, returning `true` (with `path` populated) once found
+    fn path_to(sprite: &Sprite<I>, id: Uuid, path: &mut Vec<Matrix2d>) -> bool {
+        path.push(Scene::<I>::local_transform(sprite));
+        if sprite.id() == id {
+            return true;
+        }
+        for child in sprite.children().iter() {
+            if Scene::<I>::path_to(child, id.clone(), path) {
+                return true;
+            }
+        }
+        path.pop();
+        false
+    }
+
+    /// The transform that takes `id`'s local space to world (scene) space,
+    /// composed from every ancestor's local transform down to `id` itself.
+    /// Cached per node (via interior mutability, so this stays `&self`) and
+    /// invalidated whenever `child_mut` hands out a `SpriteGuard` or a child
+    /// is removed from the scene.
+    pub fn world_transform(&self, id: Uuid) -> Option<Matrix2d> {
+        if let Some(m) = self.world_cache.borrow().get(&id) {
+            return Some(*m);
+        }
+
+        let mut path = Vec::new();
+        let found = self.children.iter().any(|child| Scene::<I>::path_to(child, id.clone(), &mut path));
+        if !found {
+            return None;
+        }
+
+        let combined = path.iter().fold(identity(), |acc, m| multiply(acc, *m));
+        self.world_cache.borrow_mut().insert(id, combined);
+        Some(combined)
+    }
+
+    /// Map a point in world (scene) space into `id`'s local space, e.g. for
+    /// hit-testing a click against a sprite that may be nested under an
+    /// animated parent.
+    pub fn point_to_local(&self, id: Uuid, point: [f64; 2]) -> Option<[f64; 2]> {
+        self.world_transform(id).map(|m| transform_pos(invert(m), point))
+    }
+
+    /// Register animation with sprite, at the default weight of `1.0`
     pub fn run(&mut self, sprite_id: Uuid, animation: &Behavior<Animation>) {
         use std::collections::hash_map::Entry::{ Vacant, Occupied };
         let animations = match self.running.entry(sprite_id) {
@@ -95,7 +870,34 @@ impl<I: ImageSize> Scene<I> {
             Occupied(entry) => entry.into_mut()
         };
         let state = State::new(animation.clone());
-        animations.push((animation.clone(), state, false));
+        animations.push((animation.clone(), state, false, 1.0));
+    }
+
+    /// Look up `name` in `seq` and register it on the sprite, the same as
+    /// calling `run` directly with the behavior it names.
+    pub fn run_named(&mut self, sprite_id: Uuid, seq: &SequenceSet, name: &str)
+        -> Result<(), SeqError>
+    {
+        match seq.get(name) {
+            Some(animation) => {
+                self.run(sprite_id, animation);
+                Ok(())
+            },
+            None => Err(SeqError::SequenceNotFound(name.to_string())),
+        }
+    }
+
+    /// Set the blend weight of a running animation of the sprite. Weights
+    /// across a sprite's behaviors are normalized in `event` when they sum
+    /// to more than `1.0`; weights that sum to less than `1.0` leave the
+    /// remainder as an implicit no-op contribution.
+    pub fn set_weight(&mut self, sprite_id: Uuid, animation: &Behavior<Animation>, weight: f64) {
+        let index = self.find(sprite_id.clone(), animation);
+        if let Some(i) = index {
+            let animations = &mut self.running[sprite_id];
+            let (b, s, paused, _) = animations.remove(i);
+            animations.push((b, s, paused, weight));
+        }
     }
 
     fn find(&self, sprite_id: Uuid, animation: &Behavior<Animation>) -> Option<uint> {
@@ -103,7 +905,7 @@ impl<I: ImageSize> Scene<I> {
         match self.running.get(&sprite_id) {
             Some(animations) => {
                 for i in range(0, animations.len()) {
-                    let (ref b, _, _) = animations[i];
+                    let (ref b, _, _, _) = animations[i];
                     if b == animation {
                         index = Some(i);
                         break;
@@ -115,53 +917,131 @@ impl<I: ImageSize> Scene<I> {
         index
     }
 
-    /// Pause a running animation of the sprite
+    /// Set `paused` on the sprite-sheet clip identified by `sprite_id` and
+    /// `animation`, if one is registered, queuing the matching lifecycle
+    /// event. Returns whether a clip was found.
+    fn set_sheet_paused(&mut self, sprite_id: Uuid, animation: &Behavior<Animation>,
+                         paused: bool, kind: AnimationEventKind) -> bool {
+        let index = match self.find_sheet(sprite_id.clone(), animation) {
+            Some(i) => i,
+            None => return false,
+        };
+        let was_paused = self.sheets.get(&sprite_id).unwrap()[index].1.paused;
+        self.sheets.get_mut(&sprite_id).unwrap()[index].1.paused = paused;
+        if was_paused != paused {
+            self.events.push(AnimationEvent {
+                sprite_id: sprite_id,
+                behavior: animation.clone(),
+                kind: kind,
+            });
+        }
+        true
+    }
+
+    /// Pause a running animation of the sprite, or a sprite-sheet clip
+    /// registered under the same `sprite_id`/`animation`
     pub fn pause(&mut self, sprite_id: Uuid, animation: &Behavior<Animation>) {
         let index = self.find(sprite_id.clone(), animation);
         if index.is_some() {
-            println!("found");
             let i = index.unwrap();
             let animations = &mut self.running[sprite_id];
-            let (b, s, _) = animations.remove(i);
-            animations.push((b, s, true));
+            let (b, s, was_paused, weight) = animations.remove(i);
+            if !was_paused {
+                self.events.push(AnimationEvent {
+                    sprite_id: sprite_id.clone(),
+                    behavior: b.clone(),
+                    kind: AnimationEventKind::Paused,
+                });
+            }
+            animations.push((b, s, true, weight));
+            return;
         }
+        self.set_sheet_paused(sprite_id, animation, true, AnimationEventKind::Paused);
     }
 
-    /// Resume a paused animation of the sprite
+    /// Resume a paused animation of the sprite, or a sprite-sheet clip
+    /// registered under the same `sprite_id`/`animation`
     pub fn resume(&mut self, sprite_id: Uuid, animation: &Behavior<Animation>) {
         let index = self.find(sprite_id.clone(), animation);
         if index.is_some() {
-            println!("found");
             let i = index.unwrap();
             let animations = &mut self.running[sprite_id];
-            let (b, s, _) = animations.remove(i);
-            animations.push((b, s, false));
+            let (b, s, was_paused, weight) = animations.remove(i);
+            if was_paused {
+                self.events.push(AnimationEvent {
+                    sprite_id: sprite_id.clone(),
+                    behavior: b.clone(),
+                    kind: AnimationEventKind::Resumed,
+                });
+            }
+            animations.push((b, s, false, weight));
+            return;
         }
+        self.set_sheet_paused(sprite_id, animation, false, AnimationEventKind::Resumed);
     }
 
-    /// Toggle an animation of the sprite
+    /// Toggle an animation of the sprite, or a sprite-sheet clip registered
+    /// under the same `sprite_id`/`animation`
     pub fn toggle(&mut self, sprite_id: Uuid, animation: &Behavior<Animation>) {
         let index = self.find(sprite_id.clone(), animation);
         if index.is_some() {
             let i = index.unwrap();
             let animations = &mut self.running[sprite_id];
-            let (b, s, paused) = animations.remove(i);
-            animations.push((b, s, !paused));
+            let (b, s, paused, weight) = animations.remove(i);
+            self.events.push(AnimationEvent {
+                sprite_id: sprite_id.clone(),
+                behavior: b.clone(),
+                kind: if paused { AnimationEventKind::Resumed } else { AnimationEventKind::Paused },
+            });
+            animations.push((b, s, !paused, weight));
+            return;
+        }
+
+        if let Some(sheet_index) = self.find_sheet(sprite_id.clone(), animation) {
+            let paused = self.sheets.get(&sprite_id).unwrap()[sheet_index].1.paused;
+            self.set_sheet_paused(sprite_id, animation, !paused,
+                if paused { AnimationEventKind::Resumed } else { AnimationEventKind::Paused });
         }
     }
 
-    /// Stop a running animation of the sprite
+    /// Stop a running animation of the sprite, or a sprite-sheet clip
+    /// registered under the same `sprite_id`/`animation`
     pub fn stop(&mut self, sprite_id: Uuid, animation: &Behavior<Animation>) {
         let index = self.find(sprite_id.clone(), animation);
         if index.is_some() {
             let i = index.unwrap();
-            &mut self.running[sprite_id].remove(i);
+            let (b, _, _, _) = self.running[sprite_id].remove(i);
+            self.events.push(AnimationEvent {
+                sprite_id: sprite_id.clone(),
+                behavior: b,
+                kind: AnimationEventKind::Stopped,
+            });
+            return;
         }
+        self.stop_sprite_sheet(sprite_id, animation);
     }
 
-    /// Stop all running animations of the sprite
+    /// Stop all running animations of the sprite, including any
+    /// sprite-sheet clips registered on it
     pub fn stop_all(&mut self, sprite_id: Uuid) {
-        self.running.remove(&sprite_id);
+        if let Some(animations) = self.running.remove(&sprite_id) {
+            for (b, _, _, _) in animations.into_iter() {
+                self.events.push(AnimationEvent {
+                    sprite_id: sprite_id.clone(),
+                    behavior: b,
+                    kind: AnimationEventKind::Stopped,
+                });
+            }
+        }
+        if let Some(clips) = self.sheets.remove(&sprite_id) {
+            for (b, _) in clips.into_iter() {
+                self.events.push(AnimationEvent {
+                    sprite_id: sprite_id.clone(),
+                    behavior: b,
+                    kind: AnimationEventKind::Stopped,
+                });
+            }
+        }
     }
 
     /// Get all the running animations in the scene
@@ -218,6 +1098,11 @@ impl<I: ImageSize> Scene<I> {
 
         if removed.is_some() {
             self.stop_all_including_children(removed.as_ref().unwrap());
+            // the removed sprite (and everything below it) may still have
+            // cached world transforms; conservative for the same reason as
+            // SpriteGuard::drop, since we don't track which cache entries
+            // belong to it without re-walking the tree we just removed it from
+            self.world_cache.borrow_mut().clear();
         }
 
         removed
@@ -242,15 +1127,21 @@ impl<I: ImageSize> Scene<I> {
         }
     }
 
-    /// Find the child by `id` from this sprite's children or grandchild, mutability
-    pub fn child_mut(&mut self, id: Uuid) -> Option<&mut Sprite<I>> {
-        match self.children_index.get(&id) {
-            Some(i) => { Some(&mut self.children[*i]) },
+    /// Find the child by `id` from this sprite's children or grandchild,
+    /// mutability. The returned `SpriteGuard` invalidates the scene's
+    /// world-transform cache when it is dropped.
+    pub fn child_mut(&mut self, id: Uuid) -> Option<SpriteGuard<I>> {
+        let Scene { ref children_index, ref mut children, ref world_cache, .. } = *self;
+
+        match children_index.get(&id) {
+            Some(i) => {
+                Some(SpriteGuard { sprite: &mut children[*i], world_cache: world_cache })
+            },
             None => {
-                for child in self.children.iter_mut() {
+                for child in children.iter_mut() {
                     match child.child_mut(id.clone()) {
                         Some(c) => {
-                            return Some(c);
+                            return Some(SpriteGuard { sprite: c, world_cache: world_cache });
                         }
                         _ => {}
                     }
@@ -261,3 +1152,100 @@ impl<I: ImageSize> Scene<I> {
         }
     }
 }
+
+#[cfg(test)]
+mod combine_tests {
+    use super::{ combine, TransformDelta };
+
+    fn delta(dx: f64, d_rotation: f64) -> TransformDelta {
+        let mut d = TransformDelta::zero();
+        d.dx = dx;
+        d.d_rotation = d_rotation;
+        d
+    }
+
+    #[test]
+    fn single_full_weight_sample_passes_through_unchanged() {
+        let samples = vec![(delta(10.0, 0.0), 1.0)];
+        let combined = combine(&samples);
+        assert_eq!(combined.dx, 10.0);
+    }
+
+    #[test]
+    fn weights_summing_to_one_average_without_normalizing() {
+        let samples = vec![(delta(10.0, 0.0), 0.5), (delta(20.0, 0.0), 0.5)];
+        let combined = combine(&samples);
+        assert_eq!(combined.dx, 15.0);
+    }
+
+    #[test]
+    fn weights_summing_over_one_are_normalized() {
+        // equal weights of 1.0 each should end up averaged, not doubled
+        let samples = vec![(delta(10.0, 0.0), 1.0), (delta(20.0, 0.0), 1.0)];
+        let combined = combine(&samples);
+        assert_eq!(combined.dx, 15.0);
+    }
+
+    #[test]
+    fn rotation_blends_the_short_way_across_the_wrap() {
+        // -170 and 170 degrees are 20 degrees apart the short way (through
+        // 180), not 340 degrees apart the long way
+        let samples = vec![(delta(0.0, -170.0), 0.5), (delta(0.0, 170.0), 0.5)];
+        let combined = combine(&samples);
+        assert!(combined.d_rotation.abs() > 175.0);
+    }
+}
+
+#[cfg(test)]
+mod sequence_set_tests {
+    use super::{ Animation, SeqError, SequenceSet };
+
+    // none of the error-path fixtures below reach an `action` step, so this
+    // is never actually called; it just needs to type-check as the closure
+    // `SequenceSet::parse` expects
+    fn never_build(_name: &str, _params: &[f64]) -> Animation {
+        unreachable!()
+    }
+
+    #[test]
+    fn empty_text_yields_no_sequences() {
+        let set = SequenceSet::parse("", never_build).unwrap();
+        assert!(set.get("anything").is_none());
+    }
+
+    #[test]
+    fn wait_without_numeric_duration_is_a_parse_error() {
+        let result = SequenceSet::parse("walk\nwait soon", never_build);
+        match result {
+            Err(SeqError::Parse(_)) => {},
+            Err(e) => panic!("expected SeqError::Parse, got {}", e),
+            Ok(_) => panic!("expected SeqError::Parse, got Ok"),
+        }
+    }
+
+    #[test]
+    fn unrecognized_step_is_a_parse_error() {
+        let result = SequenceSet::parse("walk\nteleport", never_build);
+        match result {
+            Err(SeqError::Parse(_)) => {},
+            Err(e) => panic!("expected SeqError::Parse, got {}", e),
+            Ok(_) => panic!("expected SeqError::Parse, got Ok"),
+        }
+    }
+
+    #[test]
+    fn action_with_a_non_numeric_parameter_is_a_parse_error() {
+        let result = SequenceSet::parse("jump\naction jump 10 abc 20", never_build);
+        match result {
+            Err(SeqError::Parse(_)) => {},
+            Err(e) => panic!("expected SeqError::Parse, got {}", e),
+            Ok(_) => panic!("expected SeqError::Parse, got Ok"),
+        }
+    }
+
+    #[test]
+    fn loop_prefixed_block_parses_and_is_retrievable_by_name() {
+        let set = SequenceSet::parse("walk\nloop\nwait 1.0", never_build).unwrap();
+        assert!(set.get("walk").is_some());
+    }
+}